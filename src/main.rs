@@ -9,33 +9,108 @@ use std::fmt;
 use std::fs::File;
 use std::result::Result;
 use std::io::prelude::*;
+use std::io::BufReader;
+use std::str::CharIndices;
 
 fn main() {
     let filename = "data/input-9.txt";
-    let mut f = File::open(filename).expect("file not found");
-    let mut source = String::new();
-    f.read_to_string(&mut source).expect("can't read the file");
-    let result = parse(&source);
-    if let Err(err) = result {
-        println!("syntax error: {}", err);
+
+    // --tree needs the raw source text to render `pp`, so it's the one path
+    // that still has to buffer the whole file; the normal path streams it.
+    if std::env::args().any(|arg| arg == "--tree") {
+        let mut f = File::open(filename).expect("file not found");
+        let mut source = String::new();
+        f.read_to_string(&mut source).expect("can't read the file");
+        match parse(&source) {
+            Ok(ast) => print!("{}", ast.pp(&source)),
+            Err(err) => println!("syntax error: {}", err),
+        }
         return;
     }
-    let ast = result.unwrap();
-    println!("Part 1: {}", ast.score());
-    println!("Part 2: {}", ast.garbage_len());
+
+    let f = File::open(filename).expect("file not found");
+    match parse_reader(f) {
+        Ok(ast) => {
+            println!("Part 1: {}", ast.score());
+            println!("Part 2: {}", ast.garbage_len());
+        }
+        Err(err) => println!("syntax error: {}", err),
+    }
+}
+
+/// A source of characters that tracks its own position, so `garbage` and
+/// `group` can run unmodified whether they're fed from an in-memory `&str`
+/// (`Scanner`) or an incrementally-decoded `io::Read` (`ReaderScanner`).
+trait CharSource: Iterator<Item = char> {
+    fn offset(&self) -> usize;
+    fn line(&self) -> usize;
+    fn column(&self) -> usize;
+}
+
+/// Walks a `&str` one `char` at a time, tracking byte offset, line, and column.
+struct Scanner<'a> {
+    chars: CharIndices<'a>,
+    offset: usize,
+    line: usize,
+    column: usize,
+}
+
+impl<'a> Scanner<'a> {
+    fn new(src: &'a str) -> Scanner<'a> {
+        Scanner {
+            chars: src.char_indices(),
+            offset: 0,
+            line: 1,
+            column: 0,
+        }
+    }
+}
+
+impl<'a> Iterator for Scanner<'a> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        match self.chars.next() {
+            Some((i, c)) => {
+                self.offset = i + c.len_utf8();
+                if c == '\n' {
+                    self.line += 1;
+                    self.column = 0;
+                } else {
+                    self.column += 1;
+                }
+                Some(c)
+            }
+            None => None,
+        }
+    }
+}
+
+impl<'a> CharSource for Scanner<'a> {
+    fn offset(&self) -> usize {
+        self.offset
+    }
+
+    fn line(&self) -> usize {
+        self.line
+    }
+
+    fn column(&self) -> usize {
+        self.column
+    }
 }
 
 #[derive(Debug)]
 enum AST {
-    Group(Vec<AST>),
-    Garbage(String),
+    Group { children: Vec<AST>, start: usize, end: usize },
+    Garbage { text: String, start: usize, end: usize },
 }
 
 impl AST {
     #[allow(dead_code)]
     fn count_groups(&self) -> usize {
         match self {
-            &AST::Group(ref children) => 1 + children.iter().map(AST::count_groups).sum::<usize>(),
+            &AST::Group { ref children, .. } => 1 + children.iter().map(AST::count_groups).sum::<usize>(),
             _ => 0
         }
     }
@@ -43,7 +118,7 @@ impl AST {
     fn score(&self) -> usize {
         fn depths(node: &AST, i: usize) -> usize {
             match node {
-                &AST::Group(ref children) => i + children.iter().map(|n| depths(n, i+1)).sum::<usize>(),
+                &AST::Group { ref children, .. } => i + children.iter().map(|n| depths(n, i+1)).sum::<usize>(),
                 _ => 0
             }
         }
@@ -52,83 +127,382 @@ impl AST {
 
     fn garbage_len(&self) -> usize {
         match self {
-            &AST::Garbage(ref s) => s.len(),
-            &AST::Group(ref children) => children.iter().map(AST::garbage_len).sum::<usize>(),
+            &AST::Garbage { ref text, .. } => text.len(),
+            &AST::Group { ref children, .. } => children.iter().map(AST::garbage_len).sum::<usize>(),
+        }
+    }
+
+    /// The half-open byte range `[start, end)` this node spans in the source.
+    fn range(&self) -> (usize, usize) {
+        match self {
+            &AST::Group { start, end, .. } => (start, end),
+            &AST::Garbage { start, end, .. } => (start, end),
         }
     }
+
+    /// The nodes whose range covers `offset`, innermost first, so that
+    /// callers can map a character in the input back to the group nesting
+    /// that contains it.
+    #[allow(dead_code)]
+    fn nodes_containing(&self, offset: usize) -> Vec<&AST> {
+        let mut result = Vec::new();
+        self.collect_containing(offset, &mut result);
+        result.reverse();
+        result
+    }
+
+    fn collect_containing<'a>(&'a self, offset: usize, out: &mut Vec<&'a AST>) {
+        let (start, end) = self.range();
+        if offset < start || offset >= end {
+            return;
+        }
+        out.push(self);
+        if let &AST::Group { ref children, .. } = self {
+            for child in children {
+                child.collect_containing(offset, out);
+            }
+        }
+    }
+
+    /// Renders the tree as an indented outline, using an explicit stack instead of recursion.
+    fn pp(&self, src: &str) -> String {
+        let mut out = String::new();
+        let mut stack: Vec<(usize, &AST)> = vec![(0, self)];
+        while let Some((depth, node)) = stack.pop() {
+            let indent = "  ".repeat(depth);
+            match node {
+                &AST::Group { ref children, .. } => {
+                    out.push_str(&format!("{}Group (score {})\n", indent, depth + 1));
+                    for child in children.iter().rev() {
+                        stack.push((depth + 1, child));
+                    }
+                }
+                &AST::Garbage { ref text, start, end } => {
+                    out.push_str(&format!("{}Garbage {:?} (len {})\n", indent, &src[start..end], text.len()));
+                }
+            }
+        }
+        out
+    }
 }
 
 #[derive(Debug)]
 struct ParseError {
-    message: String
+    message: String,
+    #[allow(dead_code)]
+    offset: usize,
+    line: usize,
+    column: usize,
 }
 
 impl ParseError {
-    fn result<T>(msg: &str) -> Result<T, ParseError> {
-        Err(ParseError{message: msg.to_string()})
-    }
-
-    fn format<T>(msg: String) -> Result<T, ParseError> {
-        Err(ParseError{message: msg})
+    fn new<S: CharSource>(source: &S, msg: String) -> ParseError {
+        ParseError {
+            message: msg,
+            offset: source.offset(),
+            line: source.line(),
+            column: source.column(),
+        }
     }
 }
 
 impl fmt::Display for ParseError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.message)
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
     }
 }
 
 type ParseResult = Result<AST, ParseError>;
 
-fn garbage(iter: &mut std::str::Chars) -> ParseResult {
+/// Parses garbage in panic-mode: rather than bailing on the first problem,
+/// it records a positioned diagnostic into `errors` and returns the best
+/// `AST::Garbage` it was able to build, synthesizing a missing `>` at EOF.
+/// `start` is the byte offset of the opening `<`.
+fn garbage<S: CharSource>(iter: &mut S, errors: &mut Vec<ParseError>, start: usize) -> AST {
     let mut string = String::new();
     while let Some(c) = iter.next() {
         match c {
             '!' => match iter.next() {
                 Some(_) => (),
-                None => return ParseError::result("unexpected end of string after '!'")
+                None => {
+                    errors.push(ParseError::new(iter, "unexpected end of string after '!'".to_string()));
+                    return AST::Garbage { text: string, start, end: iter.offset() };
+                }
             },
-            '>' => return Ok(AST::Garbage(string)),
+            '>' => return AST::Garbage { text: string, start, end: iter.offset() },
             _ => string.push(c)
         }
     }
-    ParseError::result("unterminated '<'")
+    errors.push(ParseError::new(iter, "unterminated '<'".to_string()));
+    AST::Garbage { text: string, start, end: iter.offset() }
 }
 
-fn group(iter: &mut std::str::Chars) -> ParseResult {
+/// Parses a group in panic-mode, synthesizing a missing `}` at EOF and
+/// recording a positioned diagnostic instead of bailing. See `garbage`.
+/// `start` is the byte offset of the opening `{`.
+fn group<S: CharSource>(iter: &mut S, errors: &mut Vec<ParseError>, start: usize) -> AST {
     let mut children = Vec::new();
     while let Some(c) = iter.next() {
         match c {
             // TODO DRY w/ parse
-            '<' => children.push(garbage(iter)?),
-            '{' => children.push(group(iter)?),
-            '}' => return Ok(AST::Group(children)),
+            '<' => children.push(garbage(iter, errors, iter.offset() - 1)),
+            '{' => children.push(group(iter, errors, iter.offset() - 1)),
+            '}' => return AST::Group { children, start, end: iter.offset() },
             _ => ()
         }
     }
-    ParseError::result("unterminated '{'")
+    errors.push(ParseError::new(iter, "unterminated '{'".to_string()));
+    AST::Group { children, start, end: iter.offset() }
+}
+
+/// Takes the tree and diagnostics that `garbage`/`group` produced and
+/// promotes the first diagnostic, if any, to an `Err`.
+fn finish_parse(ast: AST, mut errors: Vec<ParseError>) -> ParseResult {
+    if errors.is_empty() {
+        Ok(ast)
+    } else {
+        Err(errors.remove(0))
+    }
+}
+
+/// Parses `src`, collecting every diagnostic instead of stopping at the first.
+fn parse_recovering(src: &str) -> (AST, Vec<ParseError>) {
+    let mut scanner = Scanner::new(src);
+    let mut errors = Vec::new();
+    let ast = match scanner.next() {
+        Some('<') => garbage(&mut scanner, &mut errors, 0),
+        Some('{') => group(&mut scanner, &mut errors, 0),
+        Some(c) => {
+            let msg = format!("expected '<' or '{{', found {:?}", c);
+            errors.push(ParseError::new(&scanner, msg));
+            AST::Group { children: Vec::new(), start: 0, end: scanner.offset }
+        }
+        None => {
+            errors.push(ParseError::new(&scanner, "expected non-empty string".to_string()));
+            AST::Group { children: Vec::new(), start: 0, end: 0 }
+        }
+    };
+    (ast, errors)
 }
 
 fn parse(src: &str) -> ParseResult {
-    let mut iter = src.chars();
-    match iter.next() {
-        Some('<') => garbage(&mut iter),
-        Some('{') => group(&mut iter),
-        Some(c) => ParseError::format(format!("expected '<' or '{{', found {:?}", c)),
-        None => ParseError::result("expected non-empty string")
+    let (ast, errors) = parse_recovering(src);
+    finish_parse(ast, errors)
+}
+
+/// Decodes UTF-8 incrementally from a buffered byte stream one `char` at a
+/// time, tracking position like `Scanner` does over a `&str`. Beyond the
+/// `BufReader`'s fixed buffer it retains only the current multi-byte
+/// sequence being assembled, so the source is never buffered whole here;
+/// the tree that `garbage`/`group` build from it still grows with the input.
+struct ReaderScanner<R: Read> {
+    reader: BufReader<R>,
+    offset: usize,
+    line: usize,
+    column: usize,
+}
+
+impl<R: Read> ReaderScanner<R> {
+    fn new(reader: R) -> ReaderScanner<R> {
+        ReaderScanner {
+            reader: BufReader::new(reader),
+            offset: 0,
+            line: 1,
+            column: 0,
+        }
+    }
+
+    fn read_char(&mut self) -> Option<char> {
+        let mut buf = [0u8; 4];
+        let mut len = 0;
+        loop {
+            let mut byte = [0u8; 1];
+            match self.reader.read(&mut byte).expect("error reading input") {
+                0 if len == 0 => return None,
+                0 => panic!("truncated UTF-8 sequence at end of input"),
+                _ => {
+                    buf[len] = byte[0];
+                    len += 1;
+                    match std::str::from_utf8(&buf[..len]) {
+                        Ok(s) => return s.chars().next(),
+                        Err(e) if e.error_len().is_none() => continue, // need more bytes
+                        Err(e) => panic!("invalid UTF-8 in input: {}", e),
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<R: Read> Iterator for ReaderScanner<R> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        match self.read_char() {
+            Some(c) => {
+                self.offset += c.len_utf8();
+                if c == '\n' {
+                    self.line += 1;
+                    self.column = 0;
+                } else {
+                    self.column += 1;
+                }
+                Some(c)
+            }
+            None => None,
+        }
+    }
+}
+
+impl<R: Read> CharSource for ReaderScanner<R> {
+    fn offset(&self) -> usize {
+        self.offset
+    }
+
+    fn line(&self) -> usize {
+        self.line
+    }
+
+    fn column(&self) -> usize {
+        self.column
+    }
+}
+
+/// Like `parse`, but drives the same state machine over a chunked,
+/// incrementally-decoded `io::Read` instead of a fully-buffered `&str`, so
+/// the source text is never held in memory whole -- unlike `parse`, which
+/// needs it up front (e.g. via `read_to_string`) before it can begin. The
+/// returned `AST` still holds every group and all garbage text, so memory
+/// grows with the input rather than only with the nesting depth.
+fn parse_reader<R: Read>(r: R) -> ParseResult {
+    let mut scanner = ReaderScanner::new(r);
+    let mut errors = Vec::new();
+    match scanner.next() {
+        Some('<') => finish_parse(garbage(&mut scanner, &mut errors, 0), errors),
+        Some('{') => finish_parse(group(&mut scanner, &mut errors, 0), errors),
+        Some(c) => {
+            let msg = format!("expected '<' or '{{', found {:?}", c);
+            Err(ParseError::new(&scanner, msg))
+        }
+        None => Err(ParseError::new(&scanner, "expected non-empty string".to_string())),
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::fs;
+    use std::path::Path;
+
+    /// Parses every `.txt` file in `dir` and checks its `AST::pp` dump against the sibling `.txt.expected` file.
+    fn check_corpus_dir(dir: &str, expect_errors: bool) {
+        let dir_path = Path::new(env!("CARGO_MANIFEST_DIR")).join(dir);
+        let mut checked = 0;
+        for entry in fs::read_dir(&dir_path).unwrap_or_else(|e| panic!("can't read {:?}: {}", dir_path, e)) {
+            let path = entry.unwrap().path();
+            if path.extension().map_or(true, |ext| ext != "txt") {
+                continue;
+            }
+            checked += 1;
+            let src = fs::read_to_string(&path).unwrap_or_else(|e| panic!("can't read {:?}: {}", path, e));
+            let (ast, errors) = parse_recovering(&src);
+            assert_eq!(!errors.is_empty(), expect_errors, "errors for {:?}: {:?}", path, errors);
+
+            let expected_path = format!("{}.expected", path.display());
+            let expected = fs::read_to_string(&expected_path)
+                .unwrap_or_else(|e| panic!("can't read {:?}: {}", expected_path, e));
+            assert_eq!(ast.pp(&src), expected, "dump mismatch for {:?}", path);
+        }
+        assert!(checked > 0, "no .txt fixtures found in {:?}", dir_path);
+    }
+
+    #[test]
+    fn dir_tests() {
+        check_corpus_dir("tests/ok", false);
+        check_corpus_dir("tests/err", true);
+    }
+
+    /// Reconstructs source syntax from an `AST`, for the round-trip check in
+    /// `fuzz_invariants`. `AST::pp`'s indented outline isn't valid input
+    /// syntax for this grammar, so this rebuilds canonical `{...}`/`<...>`
+    /// source instead of reparsing a `pp` dump. Garbage text can never
+    /// contain a literal `>` or `!` (both are special inside `<...>`), so
+    /// wrapping it back in `<...>` always reparses to a node with the same
+    /// `score()`.
+    fn reconstruct(node: &AST) -> String {
+        match node {
+            &AST::Group { ref children, .. } => {
+                let mut s = String::from("{");
+                for (i, child) in children.iter().enumerate() {
+                    if i > 0 {
+                        s.push(',');
+                    }
+                    s.push_str(&reconstruct(child));
+                }
+                s.push('}');
+                s
+            }
+            &AST::Garbage { ref text, .. } => format!("<{}>", text),
+        }
+    }
+
+    /// A small deterministic xorshift PRNG, so this property test doesn't
+    /// depend on an external `rand` crate and stays reproducible across runs.
+    struct Xorshift(u64);
+
+    impl Xorshift {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+    }
+
+    /// Feeds arbitrary strings to `parse` and checks invariants rather than
+    /// exact answers: it never panics (implicit, since the loop completes),
+    /// a successful parse's `garbage_len()` never exceeds the source length,
+    /// and re-parsing a reconstruction of a successful parse yields an
+    /// identical `score()`.
+    #[test]
+    fn fuzz_invariants() {
+        let mut rng = Xorshift(0x9e3779b97f4a7c15);
+        let alphabet: &[u8] = b"{}<>!abc\n";
+        for _ in 0..2000 {
+            let len = (rng.next() % 40) as usize;
+            let bytes: Vec<u8> = (0..len).map(|_| alphabet[(rng.next() as usize) % alphabet.len()]).collect();
+            let src = String::from_utf8(bytes).expect("alphabet is all ASCII");
+
+            if let Ok(ast) = parse(&src) {
+                assert!(ast.garbage_len() <= src.len(), "garbage_len overflow in {:?}", src);
+
+                let reconstructed = reconstruct(&ast);
+                let reparsed = parse(&reconstructed)
+                    .unwrap_or_else(|e| panic!("reconstruction {:?} of {:?} failed to reparse: {}", reconstructed, src, e));
+                assert_eq!(reparsed.score(), ast.score(), "score mismatch: {:?} -> {:?}", src, reconstructed);
+            }
+        }
+    }
+
+    #[test]
+    fn parse_reader_matches_parse() {
+        for t in GROUP_TESTS {
+            let from_str = parse(t.source).unwrap();
+            let from_reader = parse_reader(t.source.as_bytes()).unwrap();
+            assert_eq!(from_reader.score(), from_str.score(), "in {}", t.source);
+        }
+        for t in GARBAGE_TESTS {
+            let from_str = parse(t.source).unwrap();
+            let from_reader = parse_reader(t.source.as_bytes()).unwrap();
+            assert_eq!(from_reader.garbage_len(), from_str.garbage_len(), "in {}", t.source);
+        }
+    }
 
     #[test]
     fn recognizes_garbage() {
         for t in GARBAGE_TESTS {
             match parse(t.source).unwrap() {
-                AST::Garbage(_)=> (),
+                AST::Garbage { .. } => (),
                 node => panic!("Expected Garbage; received {:?}", node)
             }
         }
@@ -138,7 +512,7 @@ mod tests {
     fn recognizes_groups() {
         for t in GROUP_TESTS {
             match parse(t.source).unwrap() {
-                AST::Group(_) => (),
+                AST::Group { .. } => (),
                 node => panic!("Expected Group; received {:?}", node)
             }
         }
@@ -165,6 +539,36 @@ mod tests {
         }
     }
 
+    #[test]
+    fn error_reports_line_and_column() {
+        let err = parse("{\n{").unwrap_err();
+        assert_eq!(err.to_string(), "2:1: unterminated '{'");
+    }
+
+    #[test]
+    fn multiple_diagnostics_accumulate() {
+        // Three unterminated constructs: the garbage never closes,
+        // and both enclosing groups are left open by the same EOF.
+        let (_, errors) = parse_recovering("{{<ab");
+        let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(messages, vec![
+            "1:5: unterminated '<'".to_string(),
+            "1:5: unterminated '{'".to_string(),
+            "1:5: unterminated '{'".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn nodes_containing_respects_half_open_ranges() {
+        let ast = parse("{{}}").unwrap(); // outer (0,4), inner (1,3)
+        let ranges = |offset: usize| -> Vec<(usize, usize)> {
+            ast.nodes_containing(offset).iter().map(|n| n.range()).collect()
+        };
+        assert_eq!(ranges(1), vec![(1, 3), (0, 4)], "offset inside both");
+        assert_eq!(ranges(3), vec![(0, 4)], "offset one past the inner group's close");
+        assert_eq!(ranges(4), vec![], "offset one past the outer group's close");
+    }
+
     #[test]
     fn count_groups() {
         for t in GROUP_TESTS {